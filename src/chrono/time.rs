@@ -3,6 +3,7 @@
  */
 
 use std::fmt;
+use std::from_str::FromStr;
 use duration::Duration;
 
 pub trait Timelike {
@@ -56,6 +57,36 @@ pub trait Timelike {
     }
 }
 
+/// The nanosecond fraction of a `TimeZ`, with the invariant
+/// `0 <= frac < 2_000_000_000`. The upper billion is the leap-second window,
+/// so all the leap-second bookkeeping lives on this newtype rather than being
+/// re-derived at every use site.
+#[deriving(Eq, TotalEq, Ord, TotalOrd, Hash)]
+struct Nanos(u32);
+
+impl Nanos {
+    /// Makes a new `Nanos`, or `None` when out of the `0..2,000,000,000` range.
+    #[inline]
+    fn new(nano: uint) -> Option<Nanos> {
+        if nano >= 2_000_000_000 {None} else {Some(Nanos(nano as u32))}
+    }
+
+    /// Returns the raw nanosecond count, which may fall in the leap window.
+    #[inline]
+    fn nanos(&self) -> u32 { let Nanos(n) = *self; n }
+
+    /// Returns true when this fraction represents a leap second.
+    #[inline]
+    fn is_leap(&self) -> bool { self.nanos() >= 1_000_000_000 }
+
+    /// Returns the nanoseconds since the last whole (possibly leap) second,
+    /// always in the `0..1,000,000,000` range.
+    #[inline]
+    fn whole_nanos(&self) -> u32 {
+        if self.is_leap() {self.nanos() - 1_000_000_000} else {self.nanos()}
+    }
+}
+
 /// ISO 8601 time without timezone.
 /// Allows for the nanosecond precision and optional leap second representation.
 #[deriving(Eq, TotalEq, Ord, TotalOrd, Hash)]
@@ -63,7 +94,7 @@ pub struct TimeZ {
     priv hour: u8,
     priv min: u8,
     priv sec: u8,
-    priv frac: u32,
+    priv frac: Nanos,
 }
 
 impl TimeZ {
@@ -98,8 +129,8 @@ impl TimeZ {
     ///
     /// Returns `None` on invalid hour, minute, second and/or nanosecond.
     pub fn from_hms_nano(hour: uint, min: uint, sec: uint, nano: uint) -> Option<TimeZ> {
-        if hour >= 24 || min >= 60 || sec >= 60 || nano >= 2_000_000_000 { return None; }
-        Some(TimeZ { hour: hour as u8, min: min as u8, sec: sec as u8, frac: nano as u32 })
+        if hour >= 24 || min >= 60 || sec >= 60 { return None; }
+        Nanos::new(nano).map(|frac| TimeZ { hour: hour as u8, min: min as u8, sec: sec as u8, frac: frac })
     }
 }
 
@@ -107,7 +138,7 @@ impl Timelike for TimeZ {
     #[inline] fn hour(&self) -> uint { self.hour as uint }
     #[inline] fn minute(&self) -> uint { self.min as uint }
     #[inline] fn second(&self) -> uint { self.sec as uint }
-    #[inline] fn nanosecond(&self) -> uint { self.frac as uint }
+    #[inline] fn nanosecond(&self) -> uint { self.frac.nanos() as uint }
 
     #[inline]
     fn with_hour(&self, hour: uint) -> Option<TimeZ> {
@@ -129,27 +160,92 @@ impl Timelike for TimeZ {
 
     #[inline]
     fn with_nanosecond(&self, nano: uint) -> Option<TimeZ> {
-        if nano >= 2_000_000_000 { return None; }
-        Some(TimeZ { frac: nano as u32, ..*self })
+        Nanos::new(nano).map(|frac| TimeZ { frac: frac, ..*self })
     }
 }
 
-impl Add<Duration,TimeZ> for TimeZ {
-    fn add(&self, rhs: &Duration) -> TimeZ {
+impl TimeZ {
+    /// Adds given `Duration` to the current time,
+    /// and also returns the number of *days* in the resulting carry.
+    ///
+    /// The second element of the returned tuple is positive when the addition
+    /// crosses one or more midnights forwards, negative when a negative
+    /// `Duration` crosses one or more midnights backwards, and zero otherwise. Only the
+    /// whole non-leap seconds contribute to the carry, so a `frac` in the
+    /// 1,000,000,000..2,000,000,000 leap-second range never advances the day
+    /// on its own.
+    pub fn overflowing_add(&self, rhs: &Duration) -> (TimeZ, i64) {
         let mut secs = self.nseconds_from_midnight() as int + rhs.nseconds() as int;
-        let mut nanos = self.frac + rhs.nnanoseconds() as u32;
+        let mut nanos = self.frac.nanos() + rhs.nnanoseconds() as u32;
 
         // always ignore leap seconds after the current whole second
-        let maxnanos = if self.frac >= 1_000_000_000 {2_000_000_000} else {1_000_000_000};
+        let maxnanos = if self.frac.is_leap() {2_000_000_000} else {1_000_000_000};
 
         if nanos >= maxnanos {
             nanos -= maxnanos;
             secs += 1;
         }
+
+        // split the whole days off the 24-hour range, flooring towards -inf,
+        // and fold in the whole days already carried by the `Duration` itself.
+        let mut days = rhs.ndays() as int + secs / 86400;
+        secs %= 86400;
+        if secs < 0 { secs += 86400; days -= 1; }
+
+        let (s, mins) = (secs % 60, secs / 60);
+        let (m, h) = (mins % 60, mins / 60);
+        (TimeZ { hour: h as u8, min: m as u8, sec: s as u8, frac: Nanos(nanos) }, days as i64)
+    }
+
+    /// Subtracts given `Duration` from the current time,
+    /// and also returns the number of *days* in the resulting carry.
+    ///
+    /// The second element of the returned tuple is negative when the
+    /// subtraction crosses one or more midnights backwards, positive when a
+    /// negative `Duration` crosses one or more midnights forwards, and zero otherwise.
+    /// As with `overflowing_add`, the leap-second fraction never borrows a day.
+    pub fn overflowing_sub(&self, rhs: &Duration) -> (TimeZ, i64) {
+        let mut secs = self.nseconds_from_midnight() as int - rhs.nseconds() as int;
+        let mut nanos = self.frac.nanos() as int - rhs.nnanoseconds() as int;
+
+        // always ignore leap seconds after the current whole second
+        let maxnanos = if self.frac.is_leap() {2_000_000_000} else {1_000_000_000};
+
+        if nanos < 0 {
+            nanos += maxnanos;
+            secs -= 1;
+        }
+
+        // split the whole days off the 24-hour range, flooring towards -inf,
+        // and fold in the whole days already carried by the `Duration` itself.
+        let mut days = -(rhs.ndays() as int) + secs / 86400;
+        secs %= 86400;
+        if secs < 0 { secs += 86400; days -= 1; }
+
         let (s, mins) = (secs % 60, secs / 60);
-        let (m, hours) = (mins % 60, mins / 60);
-        let h = hours % 24;
-        TimeZ { hour: h as u8, min: m as u8, sec: s as u8, frac: nanos }
+        let (m, h) = (mins % 60, mins / 60);
+        (TimeZ { hour: h as u8, min: m as u8, sec: s as u8, frac: Nanos(nanos as u32) }, days as i64)
+    }
+
+    /// Adds given `Duration` to the current time, returning `None` when the
+    /// result would carry outside the current day.
+    ///
+    /// Unlike the wrapping `Add` implementation this is useful when `TimeZ` is
+    /// treated as a wall clock confined to a single day, so an illegal rollover
+    /// can be detected rather than silently wrapped.
+    #[inline]
+    pub fn checked_add_signed(&self, rhs: &Duration) -> Option<TimeZ> {
+        let (time, days) = self.overflowing_add(rhs);
+        if days == 0 {Some(time)} else {None}
+    }
+}
+
+impl Add<Duration,TimeZ> for TimeZ {
+    #[inline]
+    fn add(&self, rhs: &Duration) -> TimeZ {
+        // the addition carries the day count out of the 24-hour range, discarded here.
+        let (t, _) = self.overflowing_add(rhs);
+        t
     }
 }
 
@@ -169,25 +265,135 @@ impl Sub<TimeZ,Duration> for TimeZ {
                    (self.sec  as int - rhs.sec  as int) - 1;
 
         // the fractional second from the rhs to the next non-leap second
-        let maxnanos = if rhs.frac >= 1_000_000_000 {2_000_000_000} else {1_000_000_000};
-        let nanos1 = maxnanos - rhs.frac;
+        let maxnanos = if rhs.frac.is_leap() {2_000_000_000} else {1_000_000_000};
+        let nanos1 = maxnanos - rhs.frac.nanos();
 
         // the fractional second from the last leap or non-leap second to the lhs
-        let lastfrac = if self.frac >= 1_000_000_000 {1_000_000_000} else {0};
-        let nanos2 = self.frac - lastfrac;
+        let nanos2 = self.frac.whole_nanos();
 
         Duration::seconds(secs) + Duration::nanoseconds(nanos1 as int + nanos2 as int)
     }
 }
 
-impl fmt::Show for TimeZ {
+/// The error which can be returned when parsing a `TimeZ` from a string.
+#[deriving(Eq, TotalEq, Clone)]
+pub enum ParseError {
+    /// The input did not look like an ISO 8601 time of day at all.
+    InvalidFormat,
+    /// The input was well-formed but some field was out of its valid range.
+    OutOfRange,
+}
+
+impl fmt::Show for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let (sec, nano) = if self.frac >= 1_000_000_000 {
-            (self.sec + 1, self.frac - 1_000_000_000)
+        match *self {
+            InvalidFormat => write!(f.buf, "malformed ISO 8601 time of day"),
+            OutOfRange => write!(f.buf, "time field out of range"),
+        }
+    }
+}
+
+// parses a run of decimal digits into a `uint`, or `None` if empty or non-digit.
+fn parse_digits(s: &str) -> Option<uint> {
+    if s.is_empty() { return None; }
+    let mut value = 0u;
+    for c in s.chars() {
+        if c < '0' || c > '9' { return None; }
+        value = value * 10 + (c as uint - '0' as uint);
+    }
+    Some(value)
+}
+
+// parses a `+HH:MM`, `-HHMM` or `Z`-less offset into seconds east of UTC.
+fn parse_offset(s: &str) -> Result<i32, ParseError> {
+    let negative = s.starts_with("-");
+    if !negative && !s.starts_with("+") { return Err(InvalidFormat); }
+
+    let digits: ~str = s.slice_from(1).chars().filter(|&c| c != ':').collect();
+    if digits.len() != 2 && digits.len() != 4 { return Err(InvalidFormat); }
+
+    let hour = match parse_digits(digits.as_slice().slice_to(2)) {
+        Some(v) => v, None => return Err(InvalidFormat)
+    };
+    let min = if digits.len() == 4 {
+        match parse_digits(digits.as_slice().slice_from(2)) { Some(v) => v, None => return Err(InvalidFormat) }
+    } else {
+        0
+    };
+    if hour >= 24 || min >= 60 { return Err(OutOfRange); }
+
+    let secs = (hour * 3600 + min * 60) as i32;
+    Ok(if negative {-secs} else {secs})
+}
+
+impl TimeZ {
+    /// Parses an ISO 8601 time of day, returning a descriptive `ParseError`.
+    ///
+    /// Accepts `HH:MM`, `HH:MM:SS` and an optional fractional second
+    /// introduced by either `,` or `.` with any number of digits, which is
+    /// truncated or zero-padded to the nanosecond. The `23:59:60` leap second
+    /// maps back to `sec=59, frac=1,000,000,000`, so it round-trips with the
+    /// `fmt::Show` output.
+    pub fn from_iso8601(s: &str) -> Result<TimeZ, ParseError> {
+        // split off the fractional second, which may use either separator.
+        let (main, frac_str) = match s.find(|c: char| c == ',' || c == '.') {
+            Some(i) => (s.slice_to(i), Some(s.slice_from(i + 1))),
+            None => (s, None),
+        };
+
+        let parts: ~[&str] = main.split(':').collect();
+        if parts.len() < 2 || parts.len() > 3 { return Err(InvalidFormat); }
+
+        let hour = match parse_digits(parts[0]) { Some(v) => v, None => return Err(InvalidFormat) };
+        let min  = match parse_digits(parts[1]) { Some(v) => v, None => return Err(InvalidFormat) };
+        let mut sec = if parts.len() == 3 {
+            match parse_digits(parts[2]) { Some(v) => v, None => return Err(InvalidFormat) }
         } else {
-            (self.sec, self.frac)
+            0
         };
 
+        // the fractional second, truncated or zero-padded to nanoseconds.
+        let mut nano = 0u;
+        match frac_str {
+            Some(f) => {
+                if f.is_empty() || f.chars().any(|c| c < '0' || c > '9') {
+                    return Err(InvalidFormat);
+                }
+                let mut scale = 100_000_000u;
+                for c in f.chars() {
+                    if scale == 0 { break; } // extra precision is truncated
+                    nano += (c as uint - '0' as uint) * scale;
+                    scale /= 10;
+                }
+            }
+            None => {}
+        }
+
+        // fold the leap second into the nanosecond field, as `fmt::Show` does.
+        if sec == 60 {
+            sec = 59;
+            nano += 1_000_000_000;
+        }
+
+        match TimeZ::from_hms_nano(hour, min, sec, nano) {
+            Some(t) => Ok(t),
+            None => Err(OutOfRange),
+        }
+    }
+}
+
+impl FromStr for TimeZ {
+    #[inline]
+    fn from_str(s: &str) -> Option<TimeZ> {
+        TimeZ::from_iso8601(s).ok()
+    }
+}
+
+impl fmt::Show for TimeZ {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sec = if self.frac.is_leap() {self.sec + 1} else {self.sec};
+        let nano = self.frac.whole_nanos();
+
         try!(write!(f.buf, "{:02}:{:02}:{:02}", self.hour, self.min, sec));
         if nano == 0 {
             Ok(())
@@ -201,9 +407,101 @@ impl fmt::Show for TimeZ {
     }
 }
 
+/// ISO 8601 time with a fixed timezone offset, layered on top of `TimeZ`.
+/// The offset is a whole number of seconds east of UTC.
+#[deriving(Eq, TotalEq, Ord, TotalOrd, Hash)]
+pub struct Time {
+    priv local: TimeZ,
+    priv offset: i32,
+}
+
+impl Time {
+    /// Makes a new `Time` from the local wall-clock time and a UTC offset
+    /// given in seconds east of UTC.
+    #[inline]
+    pub fn new(local: TimeZ, offset: i32) -> Time {
+        Time { local: local, offset: offset }
+    }
+
+    /// Returns the UTC offset in seconds east of UTC.
+    #[inline]
+    pub fn offset(&self) -> i32 { self.offset }
+
+    /// Parses an ISO 8601 time of day with a trailing zone designator
+    /// (`Z`, `+HH:MM` or `-HH:MM`), returning a descriptive `ParseError`.
+    pub fn from_iso8601(s: &str) -> Result<Time, ParseError> {
+        if s.ends_with("Z") {
+            let local = try!(TimeZ::from_iso8601(s.slice_to(s.len() - 1)));
+            return Ok(Time { local: local, offset: 0 });
+        }
+        match s.rfind(|c: char| c == '+' || c == '-') {
+            Some(i) => {
+                let local = try!(TimeZ::from_iso8601(s.slice_to(i)));
+                let offset = try!(parse_offset(s.slice_from(i)));
+                Ok(Time { local: local, offset: offset })
+            }
+            None => Err(InvalidFormat),
+        }
+    }
+}
+
+impl Timelike for Time {
+    #[inline] fn hour(&self) -> uint { self.local.hour() }
+    #[inline] fn minute(&self) -> uint { self.local.minute() }
+    #[inline] fn second(&self) -> uint { self.local.second() }
+    #[inline] fn nanosecond(&self) -> uint { self.local.nanosecond() }
+
+    #[inline]
+    fn with_hour(&self, hour: uint) -> Option<Time> {
+        self.local.with_hour(hour).map(|t| Time { local: t, ..*self })
+    }
+
+    #[inline]
+    fn with_minute(&self, min: uint) -> Option<Time> {
+        self.local.with_minute(min).map(|t| Time { local: t, ..*self })
+    }
+
+    #[inline]
+    fn with_second(&self, sec: uint) -> Option<Time> {
+        self.local.with_second(sec).map(|t| Time { local: t, ..*self })
+    }
+
+    #[inline]
+    fn with_nanosecond(&self, nano: uint) -> Option<Time> {
+        self.local.with_nanosecond(nano).map(|t| Time { local: t, ..*self })
+    }
+}
+
+impl Sub<Time,Duration> for Time {
+    fn sub(&self, rhs: &Time) -> Duration {
+        // normalize both operands to UTC before differencing.
+        (self.local - rhs.local) - Duration::seconds((self.offset - rhs.offset) as int)
+    }
+}
+
+impl FromStr for Time {
+    #[inline]
+    fn from_str(s: &str) -> Option<Time> {
+        Time::from_iso8601(s).ok()
+    }
+}
+
+impl fmt::Show for Time {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(self.local.fmt(f));
+        if self.offset == 0 {
+            write!(f.buf, "Z")
+        } else {
+            let (sign, off) = if self.offset < 0 {('-', -self.offset)} else {('+', self.offset)};
+            write!(f.buf, "{}{:02}:{:02}", sign, off / 3600, (off % 3600) / 60)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::from_str::from_str;
     use duration::Duration;
 
     fn hmsm(hour: uint, min: uint, sec: uint, millis: uint) -> TimeZ {
@@ -225,6 +523,37 @@ mod tests {
         check(hmsm(3, 5, 7, 900), Duration::days(12345), hmsm(3, 5, 7, 900));
     }
 
+    #[test]
+    fn test_time_overflowing_add() {
+        fn check(lhs: TimeZ, rhs: Duration, sum: TimeZ, days: i64) {
+            assert_eq!(lhs.overflowing_add(&rhs), (sum, days));
+        }
+
+        check(hmsm(3, 5, 7, 900), Duration::zero(), hmsm(3, 5, 7, 900), 0);
+        check(hmsm(3, 5, 7, 900), Duration::seconds(86399), hmsm(3, 5, 6, 900), 1);
+        check(hmsm(23, 59, 59, 900), Duration::milliseconds(100), hmsm(0, 0, 0, 0), 1);
+        check(hmsm(3, 5, 7, 900), Duration::seconds(-86399), hmsm(3, 5, 8, 900), -1);
+        // the leap second must not advance the day on its own
+        check(hmsm(23, 59, 59, 1_300), Duration::zero(), hmsm(23, 59, 59, 1_300), 0);
+
+        // `overflowing_sub` is the mirror of `overflowing_add`
+        assert_eq!(hmsm(3, 5, 7, 900).overflowing_sub(&Duration::seconds(86399)),
+                   (hmsm(3, 5, 8, 900), -1));
+        assert_eq!(hmsm(0, 0, 0, 100).overflowing_sub(&Duration::milliseconds(200)),
+                   (hmsm(23, 59, 59, 900), -1));
+    }
+
+    #[test]
+    fn test_time_checked_add_signed() {
+        assert_eq!(hmsm(3, 5, 7, 900).checked_add_signed(&Duration::milliseconds(100)),
+                   Some(hmsm(3, 5, 8, 0)));
+        assert_eq!(hmsm(3, 5, 7, 900).checked_add_signed(&Duration::zero()),
+                   Some(hmsm(3, 5, 7, 900)));
+        // carrying past midnight yields `None` rather than a wrapped value
+        assert_eq!(hmsm(23, 59, 59, 900).checked_add_signed(&Duration::milliseconds(100)), None);
+        assert_eq!(hmsm(3, 5, 7, 900).checked_add_signed(&Duration::seconds(-86399)), None);
+    }
+
     #[test]
     fn test_time_sub() {
         fn check(lhs: TimeZ, rhs: TimeZ, diff: Duration) {
@@ -251,6 +580,65 @@ mod tests {
         assert_eq!(hmsm(3, 5, 6, 1_800) + Duration::milliseconds(400), hmsm(3, 5, 7, 200));
     }
 
+    #[test]
+    fn test_nanos() {
+        assert_eq!(Nanos::new(2_000_000_000), None);
+        let regular = Nanos::new(123_456_789).unwrap();
+        assert!(!regular.is_leap());
+        assert_eq!(regular.whole_nanos(), 123_456_789);
+        let leap = Nanos::new(1_000_000_001).unwrap();
+        assert!(leap.is_leap());
+        assert_eq!(leap.whole_nanos(), 1);
+    }
+
+    #[test]
+    fn test_time_from_str() {
+        // round-trips with the `Show` output, including the leap second
+        fn roundtrip(t: TimeZ) {
+            assert_eq!(from_str::<TimeZ>(t.to_str().as_slice()), Some(t));
+        }
+        roundtrip(hmsm(23, 59, 59, 999));
+        roundtrip(hmsm(23, 59, 59, 1_000));
+        roundtrip(hmsm(23, 59, 59, 1_001));
+        roundtrip(TimeZ::from_hms_nano(0, 0, 0, 6543210).unwrap());
+
+        assert_eq!(from_str::<TimeZ>("03:05"), TimeZ::from_hms(3, 5, 0));
+        assert_eq!(from_str::<TimeZ>("03:05:07"), TimeZ::from_hms(3, 5, 7));
+        assert_eq!(from_str::<TimeZ>("03:05:07.5"), TimeZ::from_hms_milli(3, 5, 7, 500));
+        assert_eq!(from_str::<TimeZ>("03:05:07,5"), TimeZ::from_hms_milli(3, 5, 7, 500));
+
+        // malformed input and out-of-range fields are distinguished
+        assert_eq!(TimeZ::from_iso8601("03"), Err(InvalidFormat));
+        assert_eq!(TimeZ::from_iso8601("ab:cd"), Err(InvalidFormat));
+        assert_eq!(TimeZ::from_iso8601("24:00:00"), Err(OutOfRange));
+    }
+
+    #[test]
+    fn test_time_with_offset() {
+        let utc = Time::new(hmsm(9, 0, 0, 0), 0);
+        let plus2 = Time::new(hmsm(9, 0, 0, 0), 2 * 3600);
+        let plus1 = Time::new(hmsm(8, 0, 0, 0), 1 * 3600);
+        let minus5 = Time::new(hmsm(9, 0, 0, 0), -5 * 3600 - 30 * 60);
+
+        // the zone designator is appended to the `TimeZ` output
+        assert_eq!(utc.to_str(), ~"09:00:00Z");
+        assert_eq!(plus2.to_str(), ~"09:00:00+02:00");
+        assert_eq!(minus5.to_str(), ~"09:00:00-05:30");
+
+        // `Sub` normalizes both operands to UTC before differencing
+        assert_eq!(plus2 - plus1, Duration::zero());
+
+        // `Timelike` delegates to the local time
+        assert_eq!(plus2.hour(), 9);
+        assert_eq!(plus2.with_hour(10).unwrap(), Time::new(hmsm(10, 0, 0, 0), 2 * 3600));
+
+        // round-trips through `FromStr`
+        assert_eq!(from_str::<Time>("09:00:00Z"), Some(utc));
+        assert_eq!(from_str::<Time>("09:00:00+02:00"), Some(plus2));
+        assert_eq!(from_str::<Time>("09:00:00-05:30"), Some(minus5));
+        assert_eq!(Time::from_iso8601("09:00:00"), Err(InvalidFormat));
+    }
+
     #[test]
     fn test_time_fmt() {
         assert_eq!(hmsm(23, 59, 59,   999).to_str(), ~"23:59:59,999");